@@ -1,8 +1,25 @@
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
+use std::time::Duration;
 
 use reqwest::StatusCode;
 use serde_json::json;
+use tauri::{Manager, State};
+
+/// User-Agent sent on every outgoing request, so gist providers can tell
+/// traffic from this app apart from an anonymous client.
+const HTTP_USER_AGENT: &str = concat!("usermgr/", env!("CARGO_PKG_VERSION"));
+
+/// Build the single `reqwest::Client` shared across the app's lifetime.
+/// Reused across calls so connection pooling and TLS session resumption
+/// actually kick in instead of being rebuilt on every command invocation.
+fn build_http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .user_agent(HTTP_USER_AGENT)
+        .build()
+        .expect("failed to build shared reqwest client")
+}
 
 /// Build a deterministic device ID from hostname + OS.
 /// The result is a hex string that stays the same across app restarts on the same machine.
@@ -36,130 +53,1230 @@ fn redact_token_for_logs(token: &str) -> String {
     format!("{}…{}", &token[..4], &token[token.len() - 4..])
 }
 
-/// Tauri 命令：从 Gitee gist 拉取指定文件内容。
-/// 若 gist 中不存在该文件则返回 `Ok(None)`。
-#[tauri::command]
-async fn gitee_get_gist_file(
-    gist_id: String,
-    file_name: String,
-    access_token: String,
-) -> Result<Option<String>, String> {
-    if gist_id.trim().is_empty() {
-        return Err("gist_id is required".to_string());
+/// Remote gist file content paired with a hash of its exact bytes, so a
+/// caller can later tell whether another device changed it out from under
+/// them before writing back.
+struct RemoteFile {
+    content: Option<String>,
+    base_hash: String,
+}
+
+/// Hash remote gist content the same way across providers, so a base hash
+/// fetched from one call can be compared against one fetched later.
+fn hash_remote_content(content: &Option<String>) -> String {
+    let mut hasher = DefaultHasher::new();
+    content.as_deref().unwrap_or("").hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Result of a conflict-checked write: either a plain failure message or,
+/// when `expected_base_hash` no longer matches, the conflicting remote
+/// content. A structured value (Tauri commands may return any `Serialize`
+/// error) rather than a magic-prefixed string the frontend would otherwise
+/// have to split out of a `Result<(), String>`.
+#[derive(serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum SyncWriteError {
+    Conflict { remote_content: Option<String> },
+    Failed { message: String },
+}
+
+impl From<String> for SyncWriteError {
+    fn from(message: String) -> Self {
+        SyncWriteError::Failed { message }
     }
-    if file_name.trim().is_empty() {
-        return Err("file_name is required".to_string());
+}
+
+fn now_unix_seconds() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Companion file a synced file's version history is stored under, e.g.
+/// `notes.md` snapshots live in `notes.md.history.json` in the same gist.
+fn history_file_name(file_name: &str) -> String {
+    format!("{file_name}.history.json")
+}
+
+/// Number of snapshots kept per file before the oldest are dropped.
+const MAX_HISTORY_ENTRIES: usize = 50;
+
+/// How many write attempts `write_history` makes before giving up. Each
+/// attempt that finds the history file changed by another writer since our
+/// read re-merges onto the freshly fetched base for the next attempt.
+const MAX_HISTORY_WRITE_ATTEMPTS: usize = 5;
+
+/// One snapshot of a synced file, as stored in its `.history.json` companion.
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+struct VersionEntry {
+    timestamp: u64,
+    device_id: String,
+    content_hash: String,
+    content: String,
+}
+
+/// What `list_file_versions` hands the frontend: snapshot metadata without
+/// the (potentially large) content, so listing stays cheap.
+#[derive(serde::Serialize)]
+struct VersionMeta {
+    timestamp: u64,
+    device_id: String,
+    content_hash: String,
+}
+
+impl From<&VersionEntry> for VersionMeta {
+    fn from(entry: &VersionEntry) -> Self {
+        Self {
+            timestamp: entry.timestamp,
+            device_id: entry.device_id.clone(),
+            content_hash: entry.content_hash.clone(),
+        }
+    }
+}
+
+/// A history file's decoded entries paired with its own base hash, so a
+/// later write can detect whether another writer raced it between the load
+/// and the write.
+struct HistorySnapshot {
+    entries: Vec<VersionEntry>,
+    base_hash: String,
+}
+
+/// Push a new snapshot of `content` onto `entries` and cap the result to
+/// the last `max` entries. Pure so it's unit-testable without any network.
+fn append_and_cap_history(
+    mut entries: Vec<VersionEntry>,
+    content: &str,
+    device_id: String,
+    timestamp: u64,
+    max: usize,
+) -> Vec<VersionEntry> {
+    entries.push(VersionEntry {
+        timestamp,
+        device_id,
+        content_hash: hash_remote_content(&Some(content.to_string())),
+        content: content.to_string(),
+    });
+    if entries.len() > max {
+        let overflow = entries.len() - max;
+        entries.drain(0..overflow);
+    }
+    entries
+}
+
+/// Find the snapshot matching `content_hash`, if any. Pure so it's
+/// unit-testable without any network.
+fn find_version_by_hash<'a>(
+    entries: &'a [VersionEntry],
+    content_hash: &str,
+) -> Option<&'a VersionEntry> {
+    entries.iter().find(|e| e.content_hash == content_hash)
+}
+
+/// A gist-style sync backend. Gitee and Gitea expose near-identical gist
+/// APIs but differ in how the access token is carried (query string vs
+/// `Authorization` header) and in whether the host is fixed or user-chosen,
+/// so each concrete provider owns its own URL-building and auth.
+trait SyncProvider {
+    async fn get_file(
+        &self,
+        client: &reqwest::Client,
+        gist_id: &str,
+        file_name: &str,
+        token: &str,
+    ) -> Result<RemoteFile, String>;
+
+    /// Write `content` unconditionally; used by `put_file` once the
+    /// optimistic-concurrency check (if any) has passed.
+    async fn write_file(
+        &self,
+        client: &reqwest::Client,
+        gist_id: &str,
+        file_name: &str,
+        token: &str,
+        content: &str,
+    ) -> Result<(), String>;
+
+    /// Write with optimistic concurrency control. If `expected_base_hash` is
+    /// `Some`, the remote file is re-fetched first; if its hash no longer
+    /// matches, the write is skipped and `SyncWriteError::Conflict` is
+    /// returned with the remote content so the frontend can offer a merge
+    /// instead of silently clobbering another device's edit. `None` writes
+    /// unconditionally. Every successful write also appends a snapshot to
+    /// the file's `.history.json` companion, so it can be listed and
+    /// restored later; a failure to record that snapshot does not fail the
+    /// write itself, since the caller's content is already safely persisted
+    /// by then.
+    async fn put_file(
+        &self,
+        client: &reqwest::Client,
+        gist_id: &str,
+        file_name: &str,
+        token: &str,
+        content: &str,
+        expected_base_hash: Option<&str>,
+    ) -> Result<(), SyncWriteError> {
+        if let Some(expected) = expected_base_hash {
+            let remote = self.get_file(client, gist_id, file_name, token).await?;
+            if remote.base_hash != expected {
+                return Err(SyncWriteError::Conflict {
+                    remote_content: remote.content,
+                });
+            }
+        }
+        self.write_file(client, gist_id, file_name, token, content)
+            .await?;
+        let _ = self
+            .append_version(client, gist_id, file_name, token, content)
+            .await;
+        Ok(())
+    }
+
+    /// Load the decoded version history for `file_name` plus the history
+    /// file's own base hash, oldest snapshot first. An absent or unparsable
+    /// history file just means no snapshots yet.
+    async fn load_history(
+        &self,
+        client: &reqwest::Client,
+        gist_id: &str,
+        file_name: &str,
+        token: &str,
+    ) -> Result<HistorySnapshot, String> {
+        let history_name = history_file_name(file_name);
+        let remote = self.get_file(client, gist_id, &history_name, token).await?;
+        Ok(HistorySnapshot {
+            entries: remote
+                .content
+                .as_deref()
+                .and_then(|c| serde_json::from_str(c).ok())
+                .unwrap_or_default(),
+            base_hash: remote.base_hash,
+        })
+    }
+
+    /// Append a snapshot of `content` onto `snapshot`'s entries, cap it to
+    /// the last `MAX_HISTORY_ENTRIES` entries, and write it back guarded by
+    /// `snapshot.base_hash`. If another writer updated the history file in
+    /// the meantime, the base hash no longer matches; re-fetch the latest
+    /// history, re-merge our snapshot onto it, and retry up to
+    /// `MAX_HISTORY_WRITE_ATTEMPTS` times instead of silently clobbering
+    /// whatever the other writer just appended.
+    async fn write_history(
+        &self,
+        client: &reqwest::Client,
+        gist_id: &str,
+        file_name: &str,
+        token: &str,
+        mut snapshot: HistorySnapshot,
+        content: &str,
+    ) -> Result<(), String> {
+        let history_name = history_file_name(file_name);
+        for _ in 0..MAX_HISTORY_WRITE_ATTEMPTS {
+            let merged = append_and_cap_history(
+                snapshot.entries.clone(),
+                content,
+                build_device_id(),
+                now_unix_seconds(),
+                MAX_HISTORY_ENTRIES,
+            );
+            let serialized = serde_json::to_string(&merged).map_err(|e| e.to_string())?;
+
+            let remote = self.get_file(client, gist_id, &history_name, token).await?;
+            if remote.base_hash != snapshot.base_hash {
+                snapshot = HistorySnapshot {
+                    entries: remote
+                        .content
+                        .as_deref()
+                        .and_then(|c| serde_json::from_str(c).ok())
+                        .unwrap_or_default(),
+                    base_hash: remote.base_hash,
+                };
+                continue;
+            }
+
+            return self
+                .write_file(client, gist_id, &history_name, token, &serialized)
+                .await;
+        }
+        Err(format!(
+            "history for {file_name} changed too many times while recording a new version; try again"
+        ))
+    }
+
+    /// Append a snapshot of `content` to `file_name`'s history, fetching
+    /// the existing history first.
+    async fn append_version(
+        &self,
+        client: &reqwest::Client,
+        gist_id: &str,
+        file_name: &str,
+        token: &str,
+        content: &str,
+    ) -> Result<(), String> {
+        let snapshot = self.load_history(client, gist_id, file_name, token).await?;
+        self.write_history(client, gist_id, file_name, token, snapshot, content)
+            .await
+    }
+
+    /// List `file_name`'s version history as metadata only (no content).
+    async fn list_versions(
+        &self,
+        client: &reqwest::Client,
+        gist_id: &str,
+        file_name: &str,
+        token: &str,
+    ) -> Result<Vec<VersionMeta>, String> {
+        let snapshot = self.load_history(client, gist_id, file_name, token).await?;
+        Ok(snapshot.entries.iter().map(VersionMeta::from).collect())
+    }
+
+    /// Re-write `file_name` from the snapshot matching `content_hash`, then
+    /// record the restore itself as a new history entry (using the history
+    /// already fetched above as the write's base; `write_history` still
+    /// re-checks the remote history file against it before writing).
+    async fn restore_version(
+        &self,
+        client: &reqwest::Client,
+        gist_id: &str,
+        file_name: &str,
+        token: &str,
+        content_hash: &str,
+    ) -> Result<(), String> {
+        let snapshot = self.load_history(client, gist_id, file_name, token).await?;
+        let content = find_version_by_hash(&snapshot.entries, content_hash)
+            .map(|e| e.content.clone())
+            .ok_or_else(|| format!("no version with content_hash {content_hash}"))?;
+
+        self.write_file(client, gist_id, file_name, token, &content)
+            .await?;
+        let _ = self
+            .write_history(client, gist_id, file_name, token, snapshot, &content)
+            .await;
+        Ok(())
+    }
+}
+
+/// gitee.com gist backend. Auth travels as an `access_token` query parameter.
+struct GiteeProvider;
+
+impl SyncProvider for GiteeProvider {
+    async fn get_file(
+        &self,
+        client: &reqwest::Client,
+        gist_id: &str,
+        file_name: &str,
+        token: &str,
+    ) -> Result<RemoteFile, String> {
+        let url = format!(
+            "https://gitee.com/api/v5/gists/{}?access_token={}",
+            gist_id, token
+        );
+        let resp = client.get(url).send().await.map_err(|e| e.to_string())?;
+
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            return Err(format!(
+                "Gitee GET gist failed: status={} token={} body={}",
+                status.as_u16(),
+                redact_token_for_logs(token),
+                body
+            ));
+        }
+
+        // Gitee gist 响应包含以文件名为 key 的 "files" 映射。
+        let v: serde_json::Value = resp.json().await.map_err(|e| e.to_string())?;
+        let content = v
+            .get("files")
+            .and_then(|f| f.get(file_name))
+            .and_then(|f| f.get("content"))
+            .and_then(|c| c.as_str())
+            .map(|s| s.to_string());
+
+        Ok(RemoteFile {
+            base_hash: hash_remote_content(&content),
+            content,
+        })
+    }
+
+    async fn write_file(
+        &self,
+        client: &reqwest::Client,
+        gist_id: &str,
+        file_name: &str,
+        token: &str,
+        content: &str,
+    ) -> Result<(), String> {
+        let url = format!(
+            "https://gitee.com/api/v5/gists/{}?access_token={}",
+            gist_id, token
+        );
+        // Gitee gist API 所需的请求体格式。
+        let body = json!({
+            "files": {
+                file_name: {
+                    "content": content
+                }
+            }
+        });
+
+        let resp = client
+            .patch(&url)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if resp.status().is_success() {
+            return Ok(());
+        }
+
+        // 部分服务器不支持 gist 的 PATCH；为兼容性改用 PUT 重试。
+        if resp.status() == StatusCode::METHOD_NOT_ALLOWED {
+            let resp = client
+                .put(&url)
+                .json(&body)
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+            if resp.status().is_success() {
+                return Ok(());
+            }
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            return Err(format!(
+                "Gitee PUT gist failed: status={} token={} body={}",
+                status.as_u16(),
+                redact_token_for_logs(token),
+                body
+            ));
+        }
+
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        Err(format!(
+            "Gitee PATCH gist failed: status={} token={} body={}",
+            status.as_u16(),
+            redact_token_for_logs(token),
+            body
+        ))
+    }
+}
+
+/// Self-hosted Gitea gist backend. Auth travels as a bearer token in the
+/// `Authorization` header, and the host is whatever the user points at.
+struct GiteaProvider {
+    base_url: String,
+}
+
+impl GiteaProvider {
+    /// Trim a trailing slash so joined paths don't end up with `//`.
+    fn api_base(&self) -> &str {
+        self.base_url.trim_end_matches('/')
+    }
+}
+
+impl SyncProvider for GiteaProvider {
+    async fn get_file(
+        &self,
+        client: &reqwest::Client,
+        gist_id: &str,
+        file_name: &str,
+        token: &str,
+    ) -> Result<RemoteFile, String> {
+        let url = format!("{}/api/v1/gists/{}", self.api_base(), gist_id);
+        let resp = client
+            .get(url)
+            .bearer_auth(token)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            return Err(format!(
+                "Gitea GET gist failed: status={} token={} body={}",
+                status.as_u16(),
+                redact_token_for_logs(token),
+                body
+            ));
+        }
+
+        // Gitea gist 响应沿用与 Gitee 相同的 "files" 映射结构。
+        let v: serde_json::Value = resp.json().await.map_err(|e| e.to_string())?;
+        let content = v
+            .get("files")
+            .and_then(|f| f.get(file_name))
+            .and_then(|f| f.get("content"))
+            .and_then(|c| c.as_str())
+            .map(|s| s.to_string());
+
+        Ok(RemoteFile {
+            base_hash: hash_remote_content(&content),
+            content,
+        })
+    }
+
+    async fn write_file(
+        &self,
+        client: &reqwest::Client,
+        gist_id: &str,
+        file_name: &str,
+        token: &str,
+        content: &str,
+    ) -> Result<(), String> {
+        let url = format!("{}/api/v1/gists/{}", self.api_base(), gist_id);
+        let body = json!({
+            "files": {
+                file_name: {
+                    "content": content
+                }
+            }
+        });
+
+        let resp = client
+            .patch(&url)
+            .bearer_auth(token)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if resp.status().is_success() {
+            return Ok(());
+        }
+
+        // 部分 Gitea 实例不支持 gist 的 PATCH；为兼容性改用 PUT 重试。
+        if resp.status() == StatusCode::METHOD_NOT_ALLOWED {
+            let resp = client
+                .put(&url)
+                .bearer_auth(token)
+                .json(&body)
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+            if resp.status().is_success() {
+                return Ok(());
+            }
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            return Err(format!(
+                "Gitea PUT gist failed: status={} token={} body={}",
+                status.as_u16(),
+                redact_token_for_logs(token),
+                body
+            ));
+        }
+
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        Err(format!(
+            "Gitea PATCH gist failed: status={} token={} body={}",
+            status.as_u16(),
+            redact_token_for_logs(token),
+            body
+        ))
+    }
+}
+
+/// What a gist "get" command hands back to the frontend: the file content
+/// (if present) plus a hash of the exact remote bytes. Round-trip the hash
+/// back as `expected_base_hash` on the next write to get conflict detection.
+#[derive(serde::Serialize)]
+struct GistFileContent {
+    content: Option<String>,
+    base_hash: String,
+}
+
+impl From<RemoteFile> for GistFileContent {
+    fn from(remote: RemoteFile) -> Self {
+        Self {
+            content: remote.content,
+            base_hash: remote.base_hash,
+        }
     }
-    if access_token.trim().is_empty() {
-        return Err("access_token is required".to_string());
+}
+
+/// Identifies a single file to fetch from a Git repo rather than a gist:
+/// the remote URL plus an optional ref. At most one of `branch`/`revision`
+/// may be set; when neither is, the repo's default branch is used.
+struct GitSource {
+    url: String,
+    branch: Option<String>,
+    revision: Option<String>,
+}
+
+/// Trim a string and turn it into `None` if that leaves it empty, the same
+/// normalization `resolve_access_token` applies to an optional access token.
+fn non_empty_trimmed(value: Option<String>) -> Option<String> {
+    value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+impl GitSource {
+    /// Build a descriptor with `branch`/`revision` trimmed and normalized to
+    /// `None` when blank, so a frontend field that defaults to `Some("")`
+    /// rather than omitting the key is treated as "unset" everywhere below.
+    fn new(url: String, branch: Option<String>, revision: Option<String>) -> Self {
+        Self {
+            url,
+            branch: non_empty_trimmed(branch),
+            revision: non_empty_trimmed(revision),
+        }
     }
 
-    // 复用客户端实例用于本次请求链路。
-    let client = reqwest::Client::new();
+    /// Validate the descriptor the way a typical Git source validator would:
+    /// a URL is required, and branch/revision are mutually exclusive.
+    fn validate(&self) -> Result<(), String> {
+        if self.url.trim().is_empty() {
+            return Err("url is required".to_string());
+        }
+        if self.branch.is_some() && self.revision.is_some() {
+            return Err("only one of branch or revision may be set".to_string());
+        }
+        Ok(())
+    }
+
+    /// The explicitly requested ref, if any. `None` means "use the repo's
+    /// default branch".
+    fn requested_ref(&self) -> Option<&str> {
+        self.branch.as_deref().or(self.revision.as_deref())
+    }
+}
+
+/// A Git remote resolved into its provider, API origin, owner, and repo name.
+struct GitRemote {
+    provider: &'static str,
+    base_url: String,
+    owner: String,
+    repo: String,
+}
+
+/// Parse a repo URL like `https://gitee.com/owner/repo` or
+/// `https://gitea.example.com/owner/repo.git` into its provider and path
+/// parts. The provider is inferred from the host: `gitee.com` is Gitee,
+/// anything else is treated as a self-hosted Gitea instance.
+fn parse_git_remote(url: &str) -> Result<GitRemote, String> {
+    let trimmed = url.trim().trim_end_matches('/').trim_end_matches(".git");
+    let parsed = reqwest::Url::parse(trimmed).map_err(|e| format!("invalid repo url: {e}"))?;
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| "repo url has no host".to_string())?
+        .to_string();
+    let mut segments = parsed
+        .path_segments()
+        .ok_or_else(|| "repo url has no path".to_string())?;
+    let owner = segments
+        .next()
+        .filter(|s| !s.is_empty())
+        .ok_or_else(|| "repo url is missing an owner segment".to_string())?
+        .to_string();
+    let repo = segments
+        .next()
+        .filter(|s| !s.is_empty())
+        .ok_or_else(|| "repo url is missing a repo segment".to_string())?
+        .to_string();
+    let provider = if host == "gitee.com" { "gitee" } else { "gitea" };
+    let base_url = match parsed.port() {
+        Some(port) => format!("{}://{}:{}", parsed.scheme(), host, port),
+        None => format!("{}://{}", parsed.scheme(), host),
+    };
+
+    Ok(GitRemote {
+        provider,
+        base_url,
+        owner,
+        repo,
+    })
+}
+
+/// Apply a provider's auth convention (Gitee: query param, Gitea: bearer
+/// header) to an in-flight request builder.
+fn apply_provider_auth(
+    req: reqwest::RequestBuilder,
+    remote: &GitRemote,
+    token: Option<&str>,
+) -> reqwest::RequestBuilder {
+    match (remote.provider, token) {
+        ("gitee", Some(t)) => req.query(&[("access_token", t)]),
+        (_, Some(t)) => req.bearer_auth(t),
+        (_, None) => req,
+    }
+}
+
+/// Resolve a repo's default branch via its provider API, for when the
+/// caller asked for neither a branch nor a revision.
+async fn resolve_default_branch(
+    client: &reqwest::Client,
+    remote: &GitRemote,
+    token: Option<&str>,
+) -> Result<String, String> {
+    let url = match remote.provider {
+        "gitee" => format!(
+            "{}/api/v5/repos/{}/{}",
+            remote.base_url, remote.owner, remote.repo
+        ),
+        _ => format!(
+            "{}/api/v1/repos/{}/{}",
+            remote.base_url, remote.owner, remote.repo
+        ),
+    };
+    let req = apply_provider_auth(client.get(url), remote, token);
+    let resp = req.send().await.map_err(|e| e.to_string())?;
+
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        return Err(format!(
+            "failed to resolve default branch: status={} body={}",
+            status.as_u16(),
+            body
+        ));
+    }
+
+    let v: serde_json::Value = resp.json().await.map_err(|e| e.to_string())?;
+    v.get("default_branch")
+        .and_then(|b| b.as_str())
+        .map(|s| s.to_string())
+        .ok_or_else(|| "repo response is missing default_branch".to_string())
+}
+
+/// Download a single file at `git_ref` from the repo's raw-content endpoint.
+async fn fetch_raw_file(
+    client: &reqwest::Client,
+    remote: &GitRemote,
+    git_ref: &str,
+    path: &str,
+    token: Option<&str>,
+) -> Result<String, String> {
     let url = format!(
-        "https://gitee.com/api/v5/gists/{}?access_token={}",
-        gist_id.trim(),
-        access_token.trim()
+        "{}/{}/{}/raw/{}/{}",
+        remote.base_url,
+        remote.owner,
+        remote.repo,
+        git_ref,
+        path.trim_start_matches('/')
     );
-    let resp = client.get(url).send().await.map_err(|e| e.to_string())?;
+    let req = apply_provider_auth(client.get(url), remote, token);
+    let resp = req.send().await.map_err(|e| e.to_string())?;
 
     if !resp.status().is_success() {
         let status = resp.status();
         let body = resp.text().await.unwrap_or_default();
         return Err(format!(
-            "Gitee GET gist failed: status={} token={} body={}",
+            "failed to fetch repo file: status={} body={}",
             status.as_u16(),
-            redact_token_for_logs(&access_token),
             body
         ));
     }
 
-    // Gitee gist 响应包含以文件名为 key 的 "files" 映射。
-    let v: serde_json::Value = resp.json().await.map_err(|e| e.to_string())?;
-    let content = v
-        .get("files")
-        .and_then(|f| f.get(&file_name))
-        .and_then(|f| f.get("content"))
-        .and_then(|c| c.as_str())
-        .map(|s| s.to_string());
+    resp.text().await.map_err(|e| e.to_string())
+}
+
+/// Tauri 命令：从 Git 仓库（而非 gist）下载指定 ref 下的单个文件。
+/// `branch`/`revision` 最多只能设置一个；都不设置时使用仓库默认分支。若省略
+/// `access_token`，回退到为该仓库 provider 保存的本地令牌（公开仓库可以没有）。
+#[tauri::command]
+async fn fetch_repo_file(
+    app: tauri::AppHandle,
+    client: State<'_, reqwest::Client>,
+    url: String,
+    branch: Option<String>,
+    revision: Option<String>,
+    path: String,
+    access_token: Option<String>,
+) -> Result<String, String> {
+    if path.trim().is_empty() {
+        return Err("path is required".to_string());
+    }
+
+    let source = GitSource::new(url, branch, revision);
+    source.validate()?;
+
+    let remote = parse_git_remote(&source.url)?;
+    let token = access_token
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .or_else(|| load_stored_token(&app, remote.provider));
+
+    let git_ref = match source.requested_ref() {
+        Some(r) => r.to_string(),
+        None => resolve_default_branch(&client, &remote, token.as_deref()).await?,
+    };
+
+    fetch_raw_file(&client, &remote, &git_ref, path.trim(), token.as_deref()).await
+}
+
+/// Name of the on-disk token store, kept in the app's data directory.
+const TOKEN_STORE_FILE: &str = "tokens.json";
+
+/// Name of the per-install secret file that keys the token store's XOR
+/// obfuscation. Lives next to `tokens.json` but is never itself synced
+/// anywhere or exposed through any command, unlike `get_device_id`.
+const TOKEN_STORE_SECRET_FILE: &str = "token_store.secret";
+
+/// Path to the local token store file, inside the app's data directory.
+fn token_store_path(app: &tauri::AppHandle) -> Result<std::path::PathBuf, String> {
+    let dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir.join(TOKEN_STORE_FILE))
+}
+
+/// Derive a fresh local secret that isn't retrievable through any command.
+/// Not cryptographically strong randomness, just enough spread that it
+/// can't be reproduced the way `get_device_id`'s hostname+OS hash can.
+fn generate_local_secret() -> Vec<u8> {
+    let mut hasher = DefaultHasher::new();
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+        .hash(&mut hasher);
+    std::process::id().hash(&mut hasher);
+    let seed = hasher.finish().to_le_bytes();
+    seed.iter().cycle().take(32).copied().collect()
+}
+
+/// Load the per-install secret that keys the token store, generating and
+/// persisting one on first use. Kept in its own file, separate from
+/// `tokens.json`, so it's local-only and never part of anything synced.
+fn load_or_create_local_secret(app: &tauri::AppHandle) -> Result<Vec<u8>, String> {
+    let dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let path = dir.join(TOKEN_STORE_SECRET_FILE);
+
+    if let Ok(existing) = std::fs::read(&path) {
+        if !existing.is_empty() {
+            return Ok(existing);
+        }
+    }
+
+    let secret = generate_local_secret();
+    std::fs::write(&path, &secret).map_err(|e| e.to_string())?;
+    Ok(secret)
+}
+
+/// XOR `bytes` against a repeating `key`. Not a substitute for a real OS
+/// keychain or authenticated encryption — it only keeps the token store
+/// from sitting on disk as plain text. Pure so it's trivial to unit test
+/// independent of where the key comes from.
+fn xor_with_key(bytes: &[u8], key: &[u8]) -> Vec<u8> {
+    bytes
+        .iter()
+        .enumerate()
+        .map(|(i, b)| b ^ key[i % key.len()])
+        .collect()
+}
+
+/// Serialize and obfuscate the provider -> token map. Pure so the
+/// encode/decode round trip can be unit tested without any filesystem I/O.
+fn encode_token_store(
+    store: &std::collections::HashMap<String, String>,
+    secret: &[u8],
+) -> Result<Vec<u8>, String> {
+    let plain = serde_json::to_vec(store).map_err(|e| e.to_string())?;
+    Ok(xor_with_key(&plain, secret))
+}
+
+/// Inverse of `encode_token_store`.
+fn decode_token_store(
+    encoded: &[u8],
+    secret: &[u8],
+) -> Result<std::collections::HashMap<String, String>, String> {
+    let plain = xor_with_key(encoded, secret);
+    serde_json::from_slice(&plain).map_err(|e| e.to_string())
+}
+
+/// Load the provider -> token map from disk, decoding it in the process.
+/// A missing file just means no tokens have been stored yet.
+fn load_token_store(
+    app: &tauri::AppHandle,
+) -> Result<std::collections::HashMap<String, String>, String> {
+    let path = token_store_path(app)?;
+    if !path.exists() {
+        return Ok(std::collections::HashMap::new());
+    }
+    let encoded = std::fs::read(&path).map_err(|e| e.to_string())?;
+    let secret = load_or_create_local_secret(app)?;
+    decode_token_store(&encoded, &secret)
+}
+
+fn save_token_store(
+    app: &tauri::AppHandle,
+    store: &std::collections::HashMap<String, String>,
+) -> Result<(), String> {
+    let path = token_store_path(app)?;
+    let secret = load_or_create_local_secret(app)?;
+    std::fs::write(&path, encode_token_store(store, &secret)?).map_err(|e| e.to_string())
+}
+
+fn load_stored_token(app: &tauri::AppHandle, provider: &str) -> Option<String> {
+    load_token_store(app)
+        .ok()
+        .and_then(|store| store.get(provider).cloned())
+}
+
+/// Resolve the token for a command: whatever was passed explicitly, or the
+/// token stored for `provider`, in that order.
+fn resolve_access_token(
+    app: &tauri::AppHandle,
+    provider: &str,
+    access_token: Option<String>,
+) -> Result<String, String> {
+    access_token
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .or_else(|| load_stored_token(app, provider))
+        .ok_or_else(|| format!("no access token provided or stored for {provider}"))
+}
+
+/// Tauri 命令：将 `provider` 的访问令牌持久化到本地令牌库，供后续命令省略 `access_token` 时回退使用。
+#[tauri::command]
+fn store_token(app: tauri::AppHandle, provider: String, token: String) -> Result<(), String> {
+    let provider = provider.trim();
+    let token = token.trim();
+    if provider.is_empty() {
+        return Err("provider is required".to_string());
+    }
+    if token.is_empty() {
+        return Err("token is required".to_string());
+    }
 
-    Ok(content)
+    let mut store = load_token_store(&app)?;
+    store.insert(provider.to_string(), token.to_string());
+    save_token_store(&app, &store)
+}
+
+/// Tauri 命令：清除 `provider` 在本地令牌库中保存的访问令牌。
+#[tauri::command]
+fn clear_token(app: tauri::AppHandle, provider: String) -> Result<(), String> {
+    let provider = provider.trim();
+    if provider.is_empty() {
+        return Err("provider is required".to_string());
+    }
+
+    let mut store = load_token_store(&app)?;
+    store.remove(provider);
+    save_token_store(&app, &store)
+}
+
+/// Tauri 命令：查询本地令牌库中是否已保存 `provider` 的访问令牌。
+#[tauri::command]
+fn has_token(app: tauri::AppHandle, provider: String) -> Result<bool, String> {
+    let store = load_token_store(&app)?;
+    Ok(store.contains_key(provider.trim()))
+}
+
+/// Tauri 命令：列出 Gitee gist 中某文件的历史快照元数据（时间戳/设备/哈希），不含内容本身。
+/// 省略 `access_token` 时回退到本地保存的令牌。
+#[tauri::command]
+async fn list_file_versions(
+    app: tauri::AppHandle,
+    client: State<'_, reqwest::Client>,
+    gist_id: String,
+    file_name: String,
+    access_token: Option<String>,
+) -> Result<Vec<VersionMeta>, String> {
+    if gist_id.trim().is_empty() {
+        return Err("gist_id is required".to_string());
+    }
+    if file_name.trim().is_empty() {
+        return Err("file_name is required".to_string());
+    }
+    let access_token = resolve_access_token(&app, "gitee", access_token)?;
+
+    GiteeProvider
+        .list_versions(&client, gist_id.trim(), file_name.trim(), &access_token)
+        .await
+}
+
+/// Tauri 命令：将 Gitee gist 中某文件回滚到指定 `content_hash` 对应的历史快照。
+/// 省略 `access_token` 时回退到本地保存的令牌。
+#[tauri::command]
+async fn restore_file_version(
+    app: tauri::AppHandle,
+    client: State<'_, reqwest::Client>,
+    gist_id: String,
+    file_name: String,
+    access_token: Option<String>,
+    content_hash: String,
+) -> Result<(), String> {
+    if gist_id.trim().is_empty() {
+        return Err("gist_id is required".to_string());
+    }
+    if file_name.trim().is_empty() {
+        return Err("file_name is required".to_string());
+    }
+    if content_hash.trim().is_empty() {
+        return Err("content_hash is required".to_string());
+    }
+    let access_token = resolve_access_token(&app, "gitee", access_token)?;
+
+    GiteeProvider
+        .restore_version(
+            &client,
+            gist_id.trim(),
+            file_name.trim(),
+            &access_token,
+            content_hash.trim(),
+        )
+        .await
+}
+
+/// Tauri 命令：从 Gitee gist 拉取指定文件内容及其哈希。
+/// 若 gist 中不存在该文件则 `content` 为 `None`。省略 `access_token` 时回退到本地保存的令牌。
+#[tauri::command]
+async fn gitee_get_gist_file(
+    app: tauri::AppHandle,
+    client: State<'_, reqwest::Client>,
+    gist_id: String,
+    file_name: String,
+    access_token: Option<String>,
+) -> Result<GistFileContent, String> {
+    if gist_id.trim().is_empty() {
+        return Err("gist_id is required".to_string());
+    }
+    if file_name.trim().is_empty() {
+        return Err("file_name is required".to_string());
+    }
+    let access_token = resolve_access_token(&app, "gitee", access_token)?;
+
+    GiteeProvider
+        .get_file(&client, gist_id.trim(), file_name.trim(), &access_token)
+        .await
+        .map(GistFileContent::from)
 }
 
 /// Tauri 命令：使用 PATCH 更新/创建 Gitee gist 文件，失败时回退 PUT。
+/// 传入 `expected_base_hash` 可启用乐观并发检测：若远端内容已变化，返回
+/// `SyncWriteError::Conflict`（携带远端内容）而不是直接覆盖。省略 `access_token`
+/// 时回退到本地保存的令牌。
 #[tauri::command]
 async fn gitee_update_gist_file(
+    app: tauri::AppHandle,
+    client: State<'_, reqwest::Client>,
     gist_id: String,
     file_name: String,
-    access_token: String,
+    access_token: Option<String>,
     content: String,
-) -> Result<(), String> {
+    expected_base_hash: Option<String>,
+) -> Result<(), SyncWriteError> {
+    if gist_id.trim().is_empty() {
+        return Err("gist_id is required".to_string().into());
+    }
+    if file_name.trim().is_empty() {
+        return Err("file_name is required".to_string().into());
+    }
+    let access_token = resolve_access_token(&app, "gitee", access_token)?;
+
+    GiteeProvider
+        .put_file(
+            &client,
+            gist_id.trim(),
+            file_name.trim(),
+            &access_token,
+            &content,
+            expected_base_hash.as_deref(),
+        )
+        .await
+}
+
+/// Tauri 命令：通过可插拔的 `SyncProvider` 拉取 gist 文件及其哈希，支持 Gitee 与自建 Gitea。
+/// `base_url` 仅 Gitea 需要（自建实例地址），Gitee 走固定域名可省略。省略 `access_token`
+/// 时回退到本地保存的 `provider` 令牌。
+#[tauri::command]
+async fn sync_get_file(
+    app: tauri::AppHandle,
+    client: State<'_, reqwest::Client>,
+    provider: String,
+    base_url: Option<String>,
+    gist_id: String,
+    file_name: String,
+    access_token: Option<String>,
+) -> Result<GistFileContent, String> {
     if gist_id.trim().is_empty() {
         return Err("gist_id is required".to_string());
     }
     if file_name.trim().is_empty() {
         return Err("file_name is required".to_string());
     }
-    if access_token.trim().is_empty() {
-        return Err("access_token is required".to_string());
-    }
+    let access_token = resolve_access_token(&app, &provider, access_token)?;
 
-    // 复用客户端用于 PATCH/PUT 回退逻辑。
-    let client = reqwest::Client::new();
-    let url = format!(
-        "https://gitee.com/api/v5/gists/{}?access_token={}",
-        gist_id.trim(),
-        access_token.trim()
-    );
-    // Gitee gist API 所需的请求体格式。
-    let body = json!({
-        "files": {
-            file_name.trim(): {
-                "content": content
-            }
+    let gist_id = gist_id.trim();
+    let file_name = file_name.trim();
+    let access_token = access_token.as_str();
+
+    let remote = match provider.as_str() {
+        "gitee" => {
+            GiteeProvider
+                .get_file(&client, gist_id, file_name, access_token)
+                .await
         }
-    });
+        "gitea" => {
+            let base_url = base_url
+                .filter(|u| !u.trim().is_empty())
+                .ok_or_else(|| "base_url is required for gitea".to_string())?;
+            GiteaProvider { base_url }
+                .get_file(&client, gist_id, file_name, access_token)
+                .await
+        }
+        other => Err(format!("unsupported sync provider: {other}")),
+    }?;
 
-    let resp = client
-        .patch(&url)
-        .json(&body)
-        .send()
-        .await
-        .map_err(|e| e.to_string())?;
+    Ok(GistFileContent::from(remote))
+}
 
-    if resp.status().is_success() {
-        return Ok(());
+/// Tauri 命令：通过可插拔的 `SyncProvider` 写入 gist 文件，支持 Gitee 与自建 Gitea。
+/// 传入 `expected_base_hash` 可启用乐观并发检测，语义同 `gitee_update_gist_file`。省略
+/// `access_token` 时回退到本地保存的 `provider` 令牌。
+#[tauri::command]
+async fn sync_put_file(
+    app: tauri::AppHandle,
+    client: State<'_, reqwest::Client>,
+    provider: String,
+    base_url: Option<String>,
+    gist_id: String,
+    file_name: String,
+    access_token: Option<String>,
+    content: String,
+    expected_base_hash: Option<String>,
+) -> Result<(), SyncWriteError> {
+    if gist_id.trim().is_empty() {
+        return Err("gist_id is required".to_string().into());
+    }
+    if file_name.trim().is_empty() {
+        return Err("file_name is required".to_string().into());
     }
+    let access_token = resolve_access_token(&app, &provider, access_token)?;
 
-    // 部分服务器不支持 gist 的 PATCH；为兼容性改用 PUT 重试。
-    if resp.status() == StatusCode::METHOD_NOT_ALLOWED {
-        let resp = client
-            .put(&url)
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| e.to_string())?;
-        if resp.status().is_success() {
-            return Ok(());
+    let gist_id = gist_id.trim();
+    let file_name = file_name.trim();
+    let access_token = access_token.as_str();
+    let expected_base_hash = expected_base_hash.as_deref();
+
+    match provider.as_str() {
+        "gitee" => {
+            GiteeProvider
+                .put_file(
+                    &client,
+                    gist_id,
+                    file_name,
+                    access_token,
+                    &content,
+                    expected_base_hash,
+                )
+                .await
         }
-        let status = resp.status();
-        let body = resp.text().await.unwrap_or_default();
-        return Err(format!(
-            "Gitee PUT gist failed: status={} token={} body={}",
-            status.as_u16(),
-            redact_token_for_logs(&access_token),
-            body
-        ));
+        "gitea" => {
+            let base_url = base_url
+                .filter(|u| !u.trim().is_empty())
+                .ok_or_else(|| "base_url is required for gitea".to_string())?;
+            GiteaProvider { base_url }
+                .put_file(
+                    &client,
+                    gist_id,
+                    file_name,
+                    access_token,
+                    &content,
+                    expected_base_hash,
+                )
+                .await
+        }
+        other => Err(format!("unsupported sync provider: {other}").into()),
+    }
+}
+
+/// Tauri 命令：通过可插拔的 `SyncProvider` 列出某文件的历史快照元数据，支持 Gitee 与自建 Gitea。
+/// 省略 `access_token` 时回退到本地保存的 `provider` 令牌。
+#[tauri::command]
+async fn sync_list_file_versions(
+    app: tauri::AppHandle,
+    client: State<'_, reqwest::Client>,
+    provider: String,
+    base_url: Option<String>,
+    gist_id: String,
+    file_name: String,
+    access_token: Option<String>,
+) -> Result<Vec<VersionMeta>, String> {
+    if gist_id.trim().is_empty() {
+        return Err("gist_id is required".to_string());
     }
+    if file_name.trim().is_empty() {
+        return Err("file_name is required".to_string());
+    }
+    let access_token = resolve_access_token(&app, &provider, access_token)?;
 
-    let status = resp.status();
-    let body = resp.text().await.unwrap_or_default();
-    Err(format!(
-        "Gitee PATCH gist failed: status={} token={} body={}",
-        status.as_u16(),
-        redact_token_for_logs(&access_token),
-        body
-    ))
+    let gist_id = gist_id.trim();
+    let file_name = file_name.trim();
+    let access_token = access_token.as_str();
+
+    match provider.as_str() {
+        "gitee" => {
+            GiteeProvider
+                .list_versions(&client, gist_id, file_name, access_token)
+                .await
+        }
+        "gitea" => {
+            let base_url = base_url
+                .filter(|u| !u.trim().is_empty())
+                .ok_or_else(|| "base_url is required for gitea".to_string())?;
+            GiteaProvider { base_url }
+                .list_versions(&client, gist_id, file_name, access_token)
+                .await
+        }
+        other => Err(format!("unsupported sync provider: {other}")),
+    }
+}
+
+/// Tauri 命令：通过可插拔的 `SyncProvider` 将某文件回滚到指定快照，支持 Gitee 与自建 Gitea。
+/// 省略 `access_token` 时回退到本地保存的 `provider` 令牌。
+#[tauri::command]
+async fn sync_restore_file_version(
+    app: tauri::AppHandle,
+    client: State<'_, reqwest::Client>,
+    provider: String,
+    base_url: Option<String>,
+    gist_id: String,
+    file_name: String,
+    access_token: Option<String>,
+    content_hash: String,
+) -> Result<(), String> {
+    if gist_id.trim().is_empty() {
+        return Err("gist_id is required".to_string());
+    }
+    if file_name.trim().is_empty() {
+        return Err("file_name is required".to_string());
+    }
+    if content_hash.trim().is_empty() {
+        return Err("content_hash is required".to_string());
+    }
+    let access_token = resolve_access_token(&app, &provider, access_token)?;
+
+    let gist_id = gist_id.trim();
+    let file_name = file_name.trim();
+    let access_token = access_token.as_str();
+    let content_hash = content_hash.trim();
+
+    match provider.as_str() {
+        "gitee" => {
+            GiteeProvider
+                .restore_version(&client, gist_id, file_name, access_token, content_hash)
+                .await
+        }
+        "gitea" => {
+            let base_url = base_url
+                .filter(|u| !u.trim().is_empty())
+                .ok_or_else(|| "base_url is required for gitea".to_string())?;
+            GiteaProvider { base_url }
+                .restore_version(&client, gist_id, file_name, access_token, content_hash)
+                .await
+        }
+        other => Err(format!("unsupported sync provider: {other}")),
+    }
 }
 
 /// Tauri 应用入口。
@@ -168,11 +1285,180 @@ pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_geolocation::init())
+        .setup(|app| {
+            app.manage(build_http_client());
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             get_device_id,
             gitee_get_gist_file,
-            gitee_update_gist_file
+            gitee_update_gist_file,
+            sync_get_file,
+            sync_put_file,
+            fetch_repo_file,
+            store_token,
+            clear_token,
+            has_token,
+            list_file_versions,
+            restore_file_version,
+            sync_list_file_versions,
+            sync_restore_file_version
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // --- GitSource / parse_git_remote (chunk0-4) ---
+
+    #[test]
+    fn git_source_requires_url() {
+        let source = GitSource::new(String::new(), None, None);
+        assert!(source.validate().is_err());
+    }
+
+    #[test]
+    fn git_source_rejects_branch_and_revision_together() {
+        let source = GitSource::new(
+            "https://gitee.com/owner/repo".to_string(),
+            Some("main".to_string()),
+            Some("abc123".to_string()),
+        );
+        assert!(source.validate().is_err());
+    }
+
+    #[test]
+    fn git_source_blank_branch_is_treated_as_unset() {
+        let source = GitSource::new(
+            "https://gitee.com/owner/repo".to_string(),
+            Some("  ".to_string()),
+            None,
+        );
+        assert!(source.validate().is_ok());
+        assert_eq!(source.requested_ref(), None);
+    }
+
+    #[test]
+    fn git_source_trims_branch_and_revision() {
+        let branch = GitSource::new(
+            "https://gitee.com/owner/repo".to_string(),
+            Some("  main  ".to_string()),
+            None,
+        );
+        assert_eq!(branch.requested_ref(), Some("main"));
+
+        let revision = GitSource::new(
+            "https://gitee.com/owner/repo".to_string(),
+            None,
+            Some(" abc123 ".to_string()),
+        );
+        assert_eq!(revision.requested_ref(), Some("abc123"));
+    }
+
+    #[test]
+    fn git_source_defaults_to_no_requested_ref() {
+        let source = GitSource::new("https://gitee.com/owner/repo".to_string(), None, None);
+        assert_eq!(source.requested_ref(), None);
+    }
+
+    #[test]
+    fn parse_git_remote_recognizes_gitee() {
+        let remote = parse_git_remote("https://gitee.com/owner/repo").unwrap();
+        assert_eq!(remote.provider, "gitee");
+        assert_eq!(remote.owner, "owner");
+        assert_eq!(remote.repo, "repo");
+        assert_eq!(remote.base_url, "https://gitee.com");
+    }
+
+    #[test]
+    fn parse_git_remote_treats_other_hosts_as_gitea() {
+        let remote = parse_git_remote("https://git.example.com/owner/repo.git/").unwrap();
+        assert_eq!(remote.provider, "gitea");
+        assert_eq!(remote.owner, "owner");
+        assert_eq!(remote.repo, "repo");
+        assert_eq!(remote.base_url, "https://git.example.com");
+    }
+
+    #[test]
+    fn parse_git_remote_rejects_missing_repo_segment() {
+        assert!(parse_git_remote("https://gitee.com/owner").is_err());
+    }
+
+    // --- token store round trip (chunk0-5) ---
+
+    #[test]
+    fn xor_with_key_round_trips() {
+        let key = b"some-local-secret";
+        let plain = b"super-secret-token";
+        let encoded = xor_with_key(plain, key);
+        assert_ne!(encoded, plain);
+        let decoded = xor_with_key(&encoded, key);
+        assert_eq!(decoded, plain);
+    }
+
+    #[test]
+    fn encode_decode_token_store_round_trips() {
+        let mut store = std::collections::HashMap::new();
+        store.insert("gitee".to_string(), "token-a".to_string());
+        store.insert("gitea".to_string(), "token-b".to_string());
+        let key = b"per-install-secret";
+
+        let encoded = encode_token_store(&store, key).unwrap();
+        let decoded = decode_token_store(&encoded, key).unwrap();
+        assert_eq!(decoded, store);
+    }
+
+    #[test]
+    fn decode_token_store_fails_with_wrong_key() {
+        let mut store = std::collections::HashMap::new();
+        store.insert("gitee".to_string(), "token-a".to_string());
+        let encoded = encode_token_store(&store, b"right-key").unwrap();
+        assert!(decode_token_store(&encoded, b"wrong-key").is_err());
+    }
+
+    // --- version history cap/restore logic (chunk0-6) ---
+
+    fn entry(content_hash: &str) -> VersionEntry {
+        VersionEntry {
+            timestamp: 0,
+            device_id: "device".to_string(),
+            content_hash: content_hash.to_string(),
+            content: "content".to_string(),
+        }
+    }
+
+    #[test]
+    fn append_and_cap_history_appends_new_entry() {
+        let entries = append_and_cap_history(Vec::new(), "hello", "device".to_string(), 42, 50);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].timestamp, 42);
+        assert_eq!(entries[0].device_id, "device");
+        assert_eq!(entries[0].content_hash, hash_remote_content(&Some("hello".to_string())));
+    }
+
+    #[test]
+    fn append_and_cap_history_drops_oldest_first_when_over_cap() {
+        let existing = vec![entry("a"), entry("b"), entry("c")];
+        let entries = append_and_cap_history(existing, "new", "device".to_string(), 1, 3);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].content_hash, "b");
+        assert_eq!(entries[1].content_hash, "c");
+        assert_eq!(entries[2].content_hash, hash_remote_content(&Some("new".to_string())));
+    }
+
+    #[test]
+    fn find_version_by_hash_finds_match() {
+        let entries = vec![entry("a"), entry("b")];
+        let found = find_version_by_hash(&entries, "b").unwrap();
+        assert_eq!(found.content_hash, "b");
+    }
+
+    #[test]
+    fn find_version_by_hash_returns_none_when_missing() {
+        let entries = vec![entry("a")];
+        assert!(find_version_by_hash(&entries, "missing").is_none());
+    }
+}